@@ -0,0 +1,23 @@
+//! Dosing agents read from the ion contribution file.
+//!
+//! Salts contribute non-negatively to every ion. Acids and base additions
+//! (lactic/phosphoric acid, pickling lime, ...) may carry signed
+//! contributions instead, typically to lower or raise alkalinity, but the
+//! dose itself is still clamped to non-negative like a salt's.
+
+/// Kind of a dosing agent parsed from the ion contribution file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AgentKind {
+    Salt,
+    Acid,
+}
+
+impl AgentKind {
+    /// Unit the agent's dose is reported in.
+    pub fn unit(&self) -> &'static str {
+        match self {
+            AgentKind::Salt => "g",
+            AgentKind::Acid => "ml",
+        }
+    }
+}
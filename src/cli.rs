@@ -0,0 +1,61 @@
+use clap::{Parser, ValueEnum};
+
+/// Which optimizer to use to find the salt dosages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Solver {
+    /// Exact Lawson-Hanson non-negative least squares solver.
+    Nnls,
+    /// The original random-nudge hill-climbing search, kept for nonlinear
+    /// experiments where the quantity-to-concentration map is no longer
+    /// linear.
+    Stochastic,
+    /// Random-nudge search with simulated annealing, to escape local minima
+    /// that the greedy stochastic search can get stuck in.
+    Annealing,
+}
+
+/// Command-line options for the beerwater salt optimizer.
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Optimize brewing salt additions to match a target water profile")]
+pub struct Args {
+    /// Path to the ion contribution file (one salt per line)
+    #[arg(short, long, default_value = "ion_contributions.txt")]
+    pub contributions: String,
+
+    /// Path to the target concentration file
+    #[arg(short, long, default_value = "target.txt")]
+    pub target: String,
+
+    /// Water quantity in litres
+    #[arg(long = "water-litres", default_value_t = 25.0)]
+    pub water_litres: f32,
+
+    /// Size of the random nudge step in each direction, g/l
+    #[arg(short = 'e', long = "eps", default_value_t = 0.0002)]
+    pub eps: f32,
+
+    /// Number of search iterations to run
+    #[arg(short = 'n', long, default_value_t = 500_000)]
+    pub iterations: u64,
+
+    /// Seed for the deterministic RNG, so results are reproducible
+    #[arg(short, long, default_value_t = 42)]
+    pub seed: u64,
+
+    /// Which optimizer to use
+    #[arg(long, value_enum, default_value_t = Solver::Nnls)]
+    pub solver: Solver,
+
+    /// Initial temperature for the annealing solver
+    #[arg(long, default_value_t = 1.0)]
+    pub t0: f32,
+
+    /// Geometric cooling rate for the annealing solver
+    #[arg(long, default_value_t = 0.9995)]
+    pub alpha: f32,
+
+    /// Number of parallel random-restart workers for the stochastic and
+    /// annealing solvers. Defaults to the number of available CPUs.
+    #[arg(long)]
+    pub threads: Option<usize>,
+}
@@ -1,39 +1,57 @@
-use rand::Rng;
-use rand_distr::{Distribution, Normal};
+mod agent;
+mod cli;
+mod nnls;
+mod optimize;
+mod target;
+
+use agent::AgentKind;
+use clap::Parser;
+use cli::{Args, Solver};
+use optimize::Mode;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use target::Target;
 pub use std::{
     fs::{self, File},
     io::{self, BufRead, BufReader, Lines},
     process::exit,
+    thread,
 };
 
-fn nudge(eps: f32, qin: &Vec<f32>, qout: &mut Vec<f32>) {
-    let normal = Normal::new(-1.0 * eps, 1.0 * eps).unwrap();
-    let mut rng = rand::rng();
-
-    for s in 0..qin.len() {
-        qout[s] = f32::max(0.0, qin[s] + normal.sample(&mut rng));
-    }
-}
+// Conversion factor from HCO3- concentration to CaCO3-equivalent alkalinity.
+const HCO3_TO_ALKALINITY: f32 = 50.0 / 61.0;
 
 // Alkalinity based on ion concentrations
 fn alkalinity(concentrations: &Vec<f32>, i_hco3: usize) -> f32 {
-    return concentrations[i_hco3] * 50.0 / 61.0;
+    return concentrations[i_hco3] * HCO3_TO_ALKALINITY;
 }
 
-// Error function for concentrations
-fn err(c1: &Vec<f32>, c2: &Vec<f32>) -> f32 {
+// Weighted error function for concentrations. `targets[i].weight` is 0 for
+// ions the target file doesn't mention, so they are free. Concentrations
+// inside `[targets[i].min, targets[i].max]` incur no penalty; outside the
+// band the penalty grows quadratically with the distance to the nearest
+// bound.
+pub(crate) fn err(c: &Vec<f32>, targets: &Vec<Target>) -> f32 {
     let mut sum: f32 = 0.0;
-    for i in 0..c1.len() {
-        // TODO: bounds?
-        let diff = c1[i] - c2[i];
-        sum += diff * diff;
+    for i in 0..c.len() {
+        if targets[i].weight == 0.0 {
+            continue;
+        }
+        let diff = if c[i] < targets[i].min {
+            targets[i].min - c[i]
+        } else if c[i] > targets[i].max {
+            c[i] - targets[i].max
+        } else {
+            0.0
+        };
+        sum += targets[i].weight * diff * diff;
     }
     return sum;
 }
 
 // Return ion concentrations based on salt quantities
-fn conc(contributions: &Vec<Vec<f32>>, quantities: &Vec<f32>, concentrations: &mut Vec<f32>) {
-    for i in 0..quantities.len() {
+pub(crate) fn conc(contributions: &Vec<Vec<f32>>, quantities: &Vec<f32>, concentrations: &mut Vec<f32>) {
+    for i in 0..concentrations.len() {
         concentrations[i] = 0.0;
         for s in 0..quantities.len() {
             concentrations[i] += contributions[s][i] * quantities[s];
@@ -101,8 +119,59 @@ fn process_data_file_or_exit<F: FnMut(Option<Vec<&str>>) -> Option<Result<(), St
     }
 }
 
+// Run one independent optimize() search per worker thread, each starting
+// from its own random restart, and return the lowest-error result across
+// all workers.
+fn run_parallel_search(
+    contributions: &Vec<Vec<f32>>,
+    targets: &Vec<Target>,
+    eps: f32,
+    args: &Args,
+    mode: Mode,
+) -> (Vec<f32>, f32) {
+    let n_threads = match args.threads {
+        Some(0) => {
+            println!("Error: --threads must be at least 1.");
+            exit(1);
+        }
+        Some(threads) => threads,
+        None => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+    };
+
+    let results: Vec<(Vec<f32>, f32)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..n_threads)
+            .map(|worker| {
+                let mut worker_rng = StdRng::seed_from_u64(args.seed.wrapping_add(worker as u64));
+                scope.spawn(move || {
+                    optimize::optimize(
+                        contributions,
+                        targets,
+                        args.iterations,
+                        eps,
+                        mode,
+                        &mut worker_rng,
+                    )
+                })
+            })
+            .collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    });
+
+    let mut best_index = 0;
+    for (worker, (_, worker_err)) in results.iter().enumerate() {
+        println!("Thread {worker} best err: {worker_err}");
+        if *worker_err < results[best_index].1 {
+            best_index = worker;
+        }
+    }
+
+    results.into_iter().nth(best_index).unwrap()
+}
+
 fn main() {
-    let ion_contributions_file_name = "ion_contributions.txt";
+    let args = Args::parse();
+
+    let ion_contributions_file_name = args.contributions.as_str();
     let ion_contributions_file_description = "ion contribution data";
 
     let mut ion_contribution_lines = file_lines_or_exit(
@@ -141,6 +210,7 @@ fn main() {
     );
 
     let mut salts: Vec<String> = Vec::new();
+    let mut agent_kinds: Vec<AgentKind> = Vec::new();
     let mut contributions: Vec<Vec<f32>> = Vec::new();
     process_data_file_or_exit(
         &mut ion_contributions_line_number,
@@ -155,24 +225,32 @@ fn main() {
             }
 
             /* "words" now contain non-empty, non-comment lines,
-             * these are our ion contributions of salts. */
+             * these are our ion contributions of salts and acids. A name
+             * prefixed with "acid:" is an acid/base agent, allowed signed
+             * contributions (e.g. to lower or raise alkalinity); every
+             * other row is a salt, whose contributions must be
+             * non-negative. */
             if words.len() != ions.len() + 1 {
                 return Some(Err(
                     "word count should be the number of ions plus one".to_string()
                 ));
             }
 
-            let salt = words[0].to_string();
+            let (agent_kind, salt) = match words[0].strip_prefix("acid:") {
+                Some(name) => (AgentKind::Acid, name.to_string()),
+                None => (AgentKind::Salt, words[0].to_string()),
+            };
             let mut contributions_for_this_salt: Vec<f32> = Vec::new();
 
             salts.push(salt);
+            agent_kinds.push(agent_kind);
 
             let mut field_counter = 1;
             for contribution in words.iter().skip(1) {
                 field_counter += 1;
                 match contribution.parse() {
                     Ok(value) => {
-                        if value < 0.0 {
+                        if value < 0.0 && agent_kind == AgentKind::Salt {
                             return Some(Err(format!(
                                 "ion contribution at field {field_counter} is negative"
                             )));
@@ -192,20 +270,32 @@ fn main() {
         },
     );
 
+    // Make alkalinity a first-class ion the optimizer can target directly,
+    // instead of only a value computed from the final result: every agent's
+    // HCO3- contribution converts to an equivalent alkalinity contribution
+    // the same way `alkalinity()` does.
+    if let Some(hco3_index) = maybe_hco3_index {
+        ions.push("Alkalinity".to_string());
+        for agent_contributions in contributions.iter_mut() {
+            agent_contributions.push(agent_contributions[hco3_index] * HCO3_TO_ALKALINITY);
+        }
+    }
+
     // Size of step in each direction, g/l
-    let eps: f32 = 0.0002;
+    let eps: f32 = args.eps;
 
     // Water quantity in litres
-    let water_quantity: f32 = 25.0;
+    let water_quantity: f32 = args.water_litres;
 
-    let target_file_name = "target.txt";
+    let target_file_name = args.target.as_str();
     let target_file_description = "target concentration data";
 
     let mut target_lines = file_lines_or_exit(target_file_name, target_file_description);
 
     let mut target_line_number: u64 = 0;
 
-    let mut target = vec![0.0; salts.len()];
+    // Ions omitted from the target file keep Target::free(), so they are free.
+    let mut targets = vec![Target::free(); ions.len()];
 
     process_data_file_or_exit(
         &mut target_line_number,
@@ -219,8 +309,8 @@ fn main() {
                 None => return Some(Ok(())), // End of file, done reading ion contributions
             }
 
-            if words.len() != 2 {
-                return Some(Err("the number of fields must be exactly 2".to_string()));
+            if words.len() < 2 {
+                return Some(Err("the number of fields must be at least 2".to_string()));
             }
 
             let ion: &str = words[0];
@@ -245,47 +335,90 @@ fn main() {
                 None => return Some(Err(format!("unkown ion: {ion}"))),
             }
 
-            target[ion_index] = target_concentration;
+            // Default weight is 1.0 and the acceptable range is the single
+            // target value; `weight=` and `range=min:max` fields override
+            // them.
+            let mut weight: f32 = 1.0;
+            let mut range_min: f32 = target_concentration;
+            let mut range_max: f32 = target_concentration;
+
+            for field in words.iter().skip(2) {
+                if let Some(value) = field.strip_prefix("weight=") {
+                    match value.parse() {
+                        Ok(parsed) => weight = parsed,
+                        Err(parse_error) => {
+                            return Some(Err(format!("error parsing weight: {parse_error}")));
+                        }
+                    }
+                } else if let Some(value) = field.strip_prefix("range=") {
+                    let bounds: Vec<&str> = value.split(':').collect();
+                    if bounds.len() != 2 {
+                        return Some(Err("range must be of the form min:max".to_string()));
+                    }
+                    match (bounds[0].parse(), bounds[1].parse()) {
+                        (Ok(min), Ok(max)) => {
+                            if min > max {
+                                return Some(Err(format!(
+                                    "range min must not be greater than max: {value}"
+                                )));
+                            }
+                            range_min = min;
+                            range_max = max;
+                        }
+                        _ => {
+                            return Some(Err(format!("error parsing range: {value}")));
+                        }
+                    }
+                } else {
+                    return Some(Err(format!("unknown target option: {field}")));
+                }
+            }
+
+            targets[ion_index] = Target {
+                value: target_concentration,
+                weight,
+                min: range_min,
+                max: range_max,
+            };
 
             return None;
         },
     );
 
     let mut best_concentrations: Vec<f32> = vec![0.0; ions.len()];
-    let mut try_concentrations: Vec<f32> = vec![0.0; ions.len()];
-
-    // Initial random quantities, 0 - 1 g/l
-    let mut try_quantities: Vec<f32> = rand::rng()
-        .sample_iter(rand::distr::StandardUniform)
-        .take(salts.len())
-        .collect();
-    let mut best_quantities: Vec<f32> = try_quantities.clone();
 
-    conc(&contributions, &best_quantities, &mut best_concentrations);
-
-    let mut best_err: f32 = err(&target, &best_concentrations);
-
-    for i in 1..500001 {
-        nudge(eps, &best_quantities, &mut try_quantities);
-        conc(&contributions, &try_quantities, &mut try_concentrations);
-
-        let try_err = err(&target, &try_concentrations);
-
-        if try_err < best_err {
-            best_err = try_err;
-            best_concentrations.copy_from_slice(try_concentrations.as_slice());
-            best_quantities.copy_from_slice(try_quantities.as_slice());
+    let best_quantities: Vec<f32> = match args.solver {
+        Solver::Nnls => {
+            let quantities = nnls::nnls_banded(&contributions, &targets, 1e-6, 25);
+            conc(&contributions, &quantities, &mut best_concentrations);
+            quantities
         }
-
-        if i % 10000 == 0 {
-            println!("ERR @{}: {}", i, best_err);
+        Solver::Stochastic => {
+            let (quantities, _) =
+                run_parallel_search(&contributions, &targets, eps, &args, Mode::Greedy);
+            conc(&contributions, &quantities, &mut best_concentrations);
+            quantities
         }
-    }
+        Solver::Annealing => {
+            let (quantities, _) = run_parallel_search(
+                &contributions,
+                &targets,
+                eps,
+                &args,
+                Mode::Annealing {
+                    t0: args.t0,
+                    alpha: args.alpha,
+                },
+            );
+            conc(&contributions, &quantities, &mut best_concentrations);
+            quantities
+        }
+    };
     println!("");
     println!("Target concentrations:");
     println!("");
     for i in 0..ions.len() {
-        println!("{} {}", ions[i], target[i]);
+        println!("{} {}", ions[i], targets[i].value);
     }
 
     println!("");
@@ -307,9 +440,14 @@ fn main() {
     }
 
     println!("");
-    println!("Salt additions for {}l of water:", water_quantity);
+    println!("Salt and acid additions for {}l of water:", water_quantity);
     println!("");
     for s in 0..salts.len() {
-        println!("{} {}", salts[s], best_quantities[s] * water_quantity);
+        println!(
+            "{} {} {}",
+            salts[s],
+            best_quantities[s] * water_quantity,
+            agent_kinds[s].unit()
+        );
     }
 }
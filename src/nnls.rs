@@ -0,0 +1,274 @@
+//! Exact non-negative least squares solver (Lawson-Hanson active-set algorithm).
+//!
+//! The quantity -> concentration map used by this crate is linear, and the
+//! error function is a weighted sum of squares, so against fixed per-ion
+//! target values the salt-dosing problem is really `min ||A q - b||^2`
+//! subject to `q >= 0` (with rows scaled by the square roots of the weights).
+//! That has an exact, deterministic solution, which this module computes
+//! instead of relying on the random nudge search in the `optimize` module;
+//! [`nnls_banded`] extends it to per-ion tolerance bands.
+
+use crate::target::Target;
+
+/// Solve `A q ~= b` for `q >= 0` using the Lawson-Hanson active-set algorithm.
+///
+/// `a[s][i]` is the contribution of variable `s` to output `i`, matching the
+/// layout of the `contributions` matrix built elsewhere in this crate. `b` is
+/// the target vector. `tol` bounds how close to zero the KKT gradient must be
+/// before a variable is considered optimal at zero.
+pub fn nnls(a: &Vec<Vec<f32>>, b: &Vec<f32>, tol: f32, max_iterations: usize) -> Vec<f32> {
+    let n = a.len(); // number of variables (columns)
+    let m = b.len(); // number of equations (rows)
+
+    let mut q = vec![0.0f32; n];
+    let mut passive = vec![false; n]; // true if index is in the passive set P
+
+    for _ in 0..max_iterations {
+        let residual = residual(a, &q, b, m);
+
+        // w = A^T (b - A q), restricted to the active set Z.
+        let mut best_index = None;
+        let mut best_w = tol;
+        for s in 0..n {
+            if passive[s] {
+                continue;
+            }
+            let mut w_s = 0.0;
+            for i in 0..m {
+                w_s += a[s][i] * residual[i];
+            }
+            if w_s > best_w {
+                best_w = w_s;
+                best_index = Some(s);
+            }
+        }
+
+        let entering = match best_index {
+            Some(s) => s,
+            None => break, // Z is empty or every w_j <= tol: optimal.
+        };
+        passive[entering] = true;
+
+        loop {
+            let s_passive = solve_passive(a, b, &passive, m, n);
+
+            let mut min_alpha = 1.0f32;
+            let mut any_blocking = false;
+            for s in 0..n {
+                if passive[s] && s_passive[s] <= 0.0 {
+                    any_blocking = true;
+                    let alpha = q[s] / (q[s] - s_passive[s]);
+                    if alpha < min_alpha {
+                        min_alpha = alpha;
+                    }
+                }
+            }
+
+            if !any_blocking {
+                for s in 0..n {
+                    if passive[s] {
+                        q[s] = s_passive[s];
+                    }
+                }
+                break;
+            }
+
+            for s in 0..n {
+                if passive[s] {
+                    q[s] += min_alpha * (s_passive[s] - q[s]);
+                }
+            }
+            for s in 0..n {
+                if passive[s] && q[s] <= tol {
+                    passive[s] = false;
+                    q[s] = 0.0;
+                }
+            }
+        }
+    }
+
+    q
+}
+
+/// Solve the ion-targeting problem with per-ion weights and acceptable
+/// ranges. NNLS only ever solves a fixed quadratic objective against a
+/// literal target value, so a band (`target.min != target.max`) isn't
+/// something it can be handed directly; instead this alternates between
+/// solving ordinary weighted NNLS against a per-ion "effective target" and
+/// projecting that effective target onto the band around the concentration
+/// actually achieved, converging once no ion needs to move.
+pub fn nnls_banded(
+    a: &Vec<Vec<f32>>,
+    targets: &Vec<Target>,
+    tol: f32,
+    max_outer_iterations: usize,
+) -> Vec<f32> {
+    let n = a.len();
+    let m = targets.len();
+
+    let scale: Vec<f32> = targets.iter().map(|t| t.weight.max(0.0).sqrt()).collect();
+    let scaled_a: Vec<Vec<f32>> = a
+        .iter()
+        .map(|row| row.iter().zip(&scale).map(|(c, s)| c * s).collect())
+        .collect();
+
+    let mut effective_target: Vec<f32> = targets.iter().map(|t| (t.min + t.max) / 2.0).collect();
+    let mut q = vec![0.0f32; n];
+
+    for _ in 0..max_outer_iterations {
+        let scaled_b: Vec<f32> = effective_target
+            .iter()
+            .zip(&scale)
+            .map(|(t, s)| t * s)
+            .collect();
+
+        q = nnls(&scaled_a, &scaled_b, tol, n * 10);
+
+        let mut moved = false;
+        for i in 0..m {
+            let mut concentration = 0.0;
+            for s in 0..n {
+                concentration += a[s][i] * q[s];
+            }
+            let projected = concentration.clamp(targets[i].min, targets[i].max);
+            if (projected - effective_target[i]).abs() > tol {
+                moved = true;
+            }
+            effective_target[i] = projected;
+        }
+
+        if !moved {
+            break;
+        }
+    }
+
+    q
+}
+
+fn residual(a: &Vec<Vec<f32>>, q: &Vec<f32>, b: &Vec<f32>, m: usize) -> Vec<f32> {
+    let mut r = b.clone();
+    for s in 0..a.len() {
+        for i in 0..m {
+            r[i] -= a[s][i] * q[s];
+        }
+    }
+    r
+}
+
+/// Unconstrained least squares `min ||A_P s - b||^2`, restricted to the
+/// passive columns, via the normal equations `(A_P^T A_P) s = A_P^T b`.
+/// Indices outside the passive set are left at zero.
+fn solve_passive(
+    a: &Vec<Vec<f32>>,
+    b: &Vec<f32>,
+    passive: &Vec<bool>,
+    m: usize,
+    n: usize,
+) -> Vec<f32> {
+    let indices: Vec<usize> = (0..n).filter(|&s| passive[s]).collect();
+    let k = indices.len();
+
+    let mut ata = vec![vec![0.0f32; k]; k];
+    let mut atb = vec![0.0f32; k];
+
+    for (row, &s) in indices.iter().enumerate() {
+        for (col, &t) in indices.iter().enumerate() {
+            let mut dot = 0.0;
+            for i in 0..m {
+                dot += a[s][i] * a[t][i];
+            }
+            ata[row][col] = dot;
+        }
+        let mut dot = 0.0;
+        for i in 0..m {
+            dot += a[s][i] * b[i];
+        }
+        atb[row] = dot;
+    }
+
+    let x = gaussian_solve(&mut ata, &mut atb);
+
+    let mut s_full = vec![0.0f32; n];
+    for (row, &s) in indices.iter().enumerate() {
+        s_full[s] = x[row];
+    }
+    s_full
+}
+
+/// Solve `ata * x = atb` via Gaussian elimination with partial pivoting.
+fn gaussian_solve(ata: &mut Vec<Vec<f32>>, atb: &mut Vec<f32>) -> Vec<f32> {
+    let k = atb.len();
+
+    for col in 0..k {
+        let mut pivot_row = col;
+        for row in (col + 1)..k {
+            if ata[row][col].abs() > ata[pivot_row][col].abs() {
+                pivot_row = row;
+            }
+        }
+        ata.swap(col, pivot_row);
+        atb.swap(col, pivot_row);
+
+        let pivot = ata[col][col];
+        if pivot.abs() < 1e-12 {
+            continue; // Singular column: leave x[col] at 0.
+        }
+
+        for row in (col + 1)..k {
+            let factor = ata[row][col] / pivot;
+            for c in col..k {
+                ata[row][c] -= factor * ata[col][c];
+            }
+            atb[row] -= factor * atb[col];
+        }
+    }
+
+    let mut x = vec![0.0f32; k];
+    for row in (0..k).rev() {
+        let mut sum = atb[row];
+        for c in (row + 1)..k {
+            sum -= ata[row][c] * x[c];
+        }
+        x[row] = if ata[row][row].abs() < 1e-12 {
+            0.0
+        } else {
+            sum / ata[row][row]
+        };
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unconstrained_optimum_is_already_non_negative() {
+        // A = [[1, 0], [0, 1]], b = [3, 4]: the unconstrained least-squares
+        // optimum q = [3, 4] is already non-negative, so both variables
+        // should end up in the passive set untouched.
+        let a = vec![vec![1.0, 0.0], vec![0.0, 1.0]];
+        let b = vec![3.0, 4.0];
+
+        let q = nnls(&a, &b, 1e-6, 10);
+
+        assert!((q[0] - 3.0).abs() < 1e-3);
+        assert!((q[1] - 4.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn negative_component_is_pinned_to_the_active_set() {
+        // Variable 0 contributes only to equation 0, variable 1 to both.
+        // The unconstrained least-squares solution is q = [2, -1], so q[1]
+        // must be clamped to the active set at 0 and q[0] re-solved against
+        // the single remaining column, giving the non-negative optimum
+        // q = [1, 0].
+        let a = vec![vec![1.0, 0.0], vec![1.0, 1.0]];
+        let b = vec![1.0, -1.0];
+
+        let q = nnls(&a, &b, 1e-6, 10);
+
+        assert!((q[0] - 1.0).abs() < 1e-3);
+        assert!(q[1].abs() < 1e-3);
+    }
+}
@@ -0,0 +1,88 @@
+//! Stochastic salt-quantity search, kept for nonlinear experiments where the
+//! quantity-to-concentration map is no longer linear enough for the exact
+//! NNLS solver. Each call to [`optimize`] is a self-contained restart: it
+//! draws its own initial guess from `rng` and runs independently, so many
+//! calls can be fanned out across threads and merged by lowest error.
+
+use crate::target::Target;
+use crate::{conc, err};
+use rand::Rng;
+use rand::rngs::StdRng;
+use rand_distr::{Distribution, Normal};
+
+/// Which stochastic search strategy [`optimize`] should run.
+#[derive(Clone, Copy, Debug)]
+pub enum Mode {
+    /// Greedy hill climbing: only ever keep a trial that improves on the
+    /// current best.
+    Greedy,
+    /// Simulated annealing: accept worsening trials with probability
+    /// `exp(-delta / T)`, cooling `T` geometrically from `t0` by `alpha`.
+    Annealing { t0: f32, alpha: f32 },
+}
+
+fn nudge(eps: f32, qin: &Vec<f32>, qout: &mut Vec<f32>, rng: &mut StdRng) {
+    let normal = Normal::new(-1.0 * eps, 1.0 * eps).unwrap();
+
+    for s in 0..qin.len() {
+        qout[s] = f32::max(0.0, qin[s] + normal.sample(rng));
+    }
+}
+
+/// Run one independent random-restart search and return the best
+/// `(quantities, error)` pair it found.
+pub fn optimize(
+    contributions: &Vec<Vec<f32>>,
+    targets: &Vec<Target>,
+    iterations: u64,
+    eps: f32,
+    mode: Mode,
+    rng: &mut StdRng,
+) -> (Vec<f32>, f32) {
+    let n_ions = targets.len();
+    let n_salts = contributions.len();
+
+    let mut best_concentrations: Vec<f32> = vec![0.0; n_ions];
+    let mut try_concentrations: Vec<f32> = vec![0.0; n_ions];
+
+    let mut try_quantities: Vec<f32> = rng
+        .sample_iter(rand::distr::StandardUniform)
+        .take(n_salts)
+        .collect();
+    let mut current_quantities: Vec<f32> = try_quantities.clone();
+    let mut best_quantities: Vec<f32> = try_quantities.clone();
+
+    conc(contributions, &current_quantities, &mut best_concentrations);
+
+    let mut current_err: f32 = err(&best_concentrations, targets);
+    let mut best_err: f32 = current_err;
+
+    for i in 1..=iterations {
+        nudge(eps, &current_quantities, &mut try_quantities, rng);
+        conc(contributions, &try_quantities, &mut try_concentrations);
+
+        let try_err = err(&try_concentrations, targets);
+        let delta = try_err - current_err;
+
+        let accept = match mode {
+            Mode::Greedy => delta < 0.0,
+            Mode::Annealing { t0, alpha } => {
+                let t = t0 * alpha.powf(i as f32 / iterations as f32);
+                delta < 0.0 || rng.random::<f32>() < (-delta / t).exp()
+            }
+        };
+
+        if accept {
+            current_err = try_err;
+            current_quantities.copy_from_slice(try_quantities.as_slice());
+
+            if try_err < best_err {
+                best_err = try_err;
+                best_concentrations.copy_from_slice(try_concentrations.as_slice());
+                best_quantities.copy_from_slice(try_quantities.as_slice());
+            }
+        }
+    }
+
+    (best_quantities, best_err)
+}
@@ -0,0 +1,26 @@
+//! Per-ion optimization target, parsed from the target file: a concentration
+//! to aim for, together with the weight and tolerance band that control how
+//! [`crate::err`] and the NNLS solver penalize deviation from it.
+
+/// A single ion's target. Ions the target file doesn't mention keep
+/// [`Target::free`], so they impose no penalty and don't constrain the
+/// solvers.
+#[derive(Clone, Copy, Debug)]
+pub struct Target {
+    pub value: f32,
+    pub weight: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Target {
+    /// An ion omitted from the target file: weight 0, so it is free.
+    pub fn free() -> Target {
+        Target {
+            value: 0.0,
+            weight: 0.0,
+            min: 0.0,
+            max: 0.0,
+        }
+    }
+}